@@ -1,34 +1,289 @@
-#![feature(naked_functions)]
-
-use std::arch::asm;
+use std::any::Any;
+use std::arch::{asm, naked_asm};
+use std::cell::{Cell, UnsafeCell};
+use std::collections::HashMap;
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::Duration;
 
 const STACK_BUFFER_SIZE: usize = 1024 * 1024 * 5; // 5MB
-const MAX_STACKS: usize = 5;
-const PROCESS_MAIN_STACK_ID: usize = 0;
-const TRIGGER_OFFSET: isize = -32;
-const DATA_SHARE_BUFFER_SIZE: usize = 1024 * 1024 * 5; // 5MB
+const DEFAULT_INITIAL_CAPACITY: usize = 5;
+const SIG_ALT_STACK_SIZE: usize = 1024 * 64;
+const DEFAULT_SCHEDULER_INTERVAL: Duration = Duration::from_millis(10);
+const PAGE_SIZE: usize = 4096;
+const DEFAULT_GUARD_PAGES: usize = 1;
+const MAILBOX_CAPACITY: usize = 64;
+
+// The guard page(s) of every live stack, so the SIGSEGV/SIGBUS handler can
+// recover which `stack_id` overflowed from the fault address alone. Keyed
+// by (stack_id, guard_base, guard_len) and deliberately NOT the usable
+// buffer past the guard page - a fault anywhere in the 5MB usable region
+// is a bug elsewhere, not an overflow, and should still be reported as an
+// unattributed segfault rather than mislabeled. Addresses rather than raw
+// pointers so the registry stays `Send`/`Sync`.
+static STACK_REGIONS: Mutex<Vec<(usize, usize, usize)>> = Mutex::new(Vec::new());
+
+fn register_stack_region(stack_id: usize, guard_base: usize, guard_len: usize) {
+    STACK_REGIONS
+        .lock()
+        .unwrap()
+        .push((stack_id, guard_base, guard_len));
+}
+
+// Removes a stack's guard-page entry once its `MmapStack` is unmapped, so
+// a later `mmap` that reuses the freed address range can't have its own
+// faults misattributed to a dead `stack_id`.
+fn deregister_stack_region(guard_base: usize) {
+    STACK_REGIONS
+        .lock()
+        .unwrap()
+        .retain(|&(_, base, _)| base != guard_base);
+}
+
+/// How many `Stack`s are alive right now versus the historical peak, the
+/// configurable ceiling `Stack::new` enforces, and the low/high watermarks
+/// governing `free_buffers()`. Global rather than per-worker for the same
+/// reason `STACK_REGIONS` is: stacks migrate between workers via
+/// work-stealing, mailboxes and `resume`, so there is no single owner to
+/// ask.
+struct PoolStats {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+    max_stacks: AtomicUsize,
+    // Idle `MmapStack`s are kept in `free_buffers()` for reuse up to this
+    // many; a `Stack::drop` past it is really munmapped instead of cached.
+    high_watermark: AtomicUsize,
+    // Once `current` live stacks drops below this, `free_buffers()` is
+    // madvise(MADV_DONTNEED)'d - the mappings stay reusable, but idle
+    // physical memory is handed back to the OS rather than held hostage
+    // by a burst of concurrency that already subsided.
+    low_watermark: AtomicUsize,
+}
+
+fn pool_stats() -> &'static PoolStats {
+    static CELL: OnceLock<PoolStats> = OnceLock::new();
+    CELL.get_or_init(|| PoolStats {
+        current: AtomicUsize::new(0),
+        peak: AtomicUsize::new(0),
+        max_stacks: AtomicUsize::new(usize::MAX),
+        high_watermark: AtomicUsize::new(DEFAULT_INITIAL_CAPACITY),
+        low_watermark: AtomicUsize::new(DEFAULT_INITIAL_CAPACITY / 2),
+    })
+}
+
+/// `MmapStack`s recycled from finished `Stack`s, keyed implicitly by
+/// `guard_pages` (checked on reuse - a mismatch just mmaps fresh and lets
+/// the cached one drop for real). This is what makes `Stack::new` lazy:
+/// a stack's backing memory is only actually mmap'd when the free list
+/// can't already satisfy it.
+fn free_buffers() -> &'static Mutex<Vec<MmapStack>> {
+    static CELL: OnceLock<Mutex<Vec<MmapStack>>> = OnceLock::new();
+    CELL.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Pops a same-sized idle buffer out of `free_buffers()`, if one is
+/// cached, instead of mmapping a fresh one.
+fn take_free_buffer(guard_pages: usize) -> Option<MmapStack> {
+    let mut free = free_buffers().lock().unwrap();
+    let pos = free.iter().position(|buf| buf.guard_pages == guard_pages)?;
+    Some(free.swap_remove(pos))
+}
+
+/// Either caches `buffer` in `free_buffers()` for the next `Stack::new`
+/// to reuse, or - once `high_watermark` idle buffers are already cached -
+/// lets it actually drop (munmap). Called from `Stack::drop`.
+fn recycle_or_release(buffer: MmapStack) {
+    let high = pool_stats().high_watermark.load(Ordering::Relaxed);
+    let mut free = free_buffers().lock().unwrap();
+    if free.len() < high {
+        free.push(buffer);
+    } else {
+        drop(free);
+        drop(buffer);
+    }
+}
+
+/// Hands idle cached buffers' physical memory back to the OS via
+/// `madvise(MADV_DONTNEED)` without unmapping them, so they stay
+/// instantly reusable but no longer pin pages nobody is touching.
+fn release_idle_buffers() {
+    let free = free_buffers().lock().unwrap();
+    for buf in free.iter() {
+        unsafe {
+            libc::madvise(
+                buf.base_ptr.add(buf.guard_bytes) as *mut libc::c_void,
+                buf.len(),
+                libc::MADV_DONTNEED,
+            );
+        }
+    }
+}
+
+/// Snapshot returned by `LeydiStacks::stats()`.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolUsage {
+    pub current: usize,
+    pub peak: usize,
+    pub idle: usize,
+}
 
-static mut RUNTIME_PTR: *mut LeydiStacks = 0 as *mut LeydiStacks;
+thread_local! {
+    // Per-thread pointer to the `Worker` driving the OS thread it was
+    // spawned on. Replaces the old single global `RUNTIME_PTR` now that
+    // stacks are scheduled M:N across multiple worker threads: a
+    // `Worker`'s context switching only ever touches its own thread-local
+    // state, so coroutine code (`next_stack`, `finish_and_next_stack`, ...)
+    // always resolves to whichever worker happens to be running it.
+    static WORKER_PTR: Cell<*mut Worker> = const { Cell::new(std::ptr::null_mut()) };
+
+    // Alternate signal stack for SIGVTALRM, one per worker thread (POSIX
+    // `sigaltstack` registration is thread-local).
+    static SIG_ALT_STACK: UnsafeCell<[u8; SIG_ALT_STACK_SIZE]> =
+        const { UnsafeCell::new([0u8; SIG_ALT_STACK_SIZE]) };
+
+    // Held for the duration of the critical section inside `schedule_next`
+    // so a SIGVTALRM landing mid-switch is dropped instead of racing the
+    // context save/restore. The periodic itimer re-fires on its own, so the
+    // next tick picks up the preemption once the guard is clear again.
+    static IN_SWITCH: Cell<bool> = const { Cell::new(false) };
+}
 
 pub struct LeydiStacks {
-    stack_pool: Vec<Stack>,
-    curr_stack_id: usize,
-    data_buffer: ShareBuffer,
+    worker_count: usize,
+    seed_stacks: Vec<fn()>,
+    scheduler_interval: Duration,
+    guard_pages: usize,
+    initial_capacity: usize,
 }
 
 #[allow(dead_code)]
 struct Stack {
     stack_id: usize,
     state: State,
-    stack_buffer: Vec<u8>,
+    stack_buffer: MmapStack,
     stack_context: StackContext,
+    // Value handed out by the most recent `stack_yield`, read back by
+    // `resume`. `None` once consumed.
+    yielded: Option<Message>,
+    // Where `stack_yield`/`finish_and_next_stack` should switch back to
+    // when this stack suspends or finishes, set by `resume` just before
+    // switching in. `None` means this stack was never driven by `resume`
+    // (e.g. it is a seed stack scheduled normally), in which case it
+    // falls back to the worker's `host_context`.
+    resume_into: Option<*mut StackContext>,
+    // The function this stack starts at, read back by
+    // `stack_entry_trampoline` once it's actually running - see its doc
+    // comment for why `rip` can't just point straight at it.
+    entry_fn: Option<fn()>,
+}
+
+// Safety: a `Stack` only mutates its own buffer/context while it is
+// `RUNNING`, and ownership of a `RUNNING` stack never moves between
+// threads - only `READY` stacks sitting in a `ChaseLevDeque` do.
+unsafe impl Send for Stack {}
+
+/// A stack's backing memory: one or more `PROT_NONE` guard pages at the
+/// low address end (where a deeply-recursing stack grows into), followed
+/// by the usable `STACK_BUFFER_SIZE` region. Touching a guard page raises
+/// SIGSEGV/SIGBUS instead of silently smashing whatever heap allocation
+/// happened to land below the old flat `Vec<u8>` buffer.
+struct MmapStack {
+    base_ptr: *mut u8,
+    region_len: usize,
+    guard_bytes: usize,
+    guard_pages: usize,
+}
+
+impl MmapStack {
+    /// Only mmaps - does not register a `STACK_REGIONS` entry, since a
+    /// fresh buffer and one just pulled out of `free_buffers()` go
+    /// through the same registration step in `Stack::new` (a recycled
+    /// buffer is re-registered under its *new* `stack_id` each time).
+    fn new(guard_pages: usize) -> Self {
+        let guard_bytes = guard_pages.max(1) * PAGE_SIZE;
+        let region_len = guard_bytes + STACK_BUFFER_SIZE;
+
+        unsafe {
+            let base_ptr = libc::mmap(
+                std::ptr::null_mut(),
+                region_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if base_ptr == libc::MAP_FAILED {
+                panic!("mmap failed: {}", std::io::Error::last_os_error());
+            }
+            if libc::mprotect(base_ptr, guard_bytes, libc::PROT_NONE) != 0 {
+                panic!("mprotect failed: {}", std::io::Error::last_os_error());
+            }
+
+            MmapStack {
+                base_ptr: base_ptr as *mut u8,
+                region_len,
+                guard_bytes,
+                guard_pages,
+            }
+        }
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        unsafe { self.base_ptr.add(self.guard_bytes) }
+    }
+
+    fn len(&self) -> usize {
+        self.region_len - self.guard_bytes
+    }
+
+    /// A placeholder with no backing mapping, used only to fill the hole
+    /// `Stack::drop` leaves via `mem::replace` once it has taken the real
+    /// buffer out to recycle or release - never touched as a stack.
+    fn invalid() -> Self {
+        MmapStack {
+            base_ptr: std::ptr::null_mut(),
+            region_len: 0,
+            guard_bytes: 0,
+            guard_pages: 0,
+        }
+    }
 }
 
+impl Drop for MmapStack {
+    // Only unmaps. The `STACK_REGIONS` entry is removed as soon as a
+    // buffer stops backing a live `Stack` (see `Stack::drop`), whether it
+    // ends up recycled into `free_buffers()` or really dropped here - an
+    // idle, cached buffer is never a registered region either way.
+    fn drop(&mut self) {
+        if self.base_ptr.is_null() {
+            return;
+        }
+        unsafe {
+            libc::munmap(self.base_ptr as *mut libc::c_void, self.region_len);
+        }
+    }
+}
+
+// Safety: the guard page makes this a single, exclusively-owned mapping;
+// moving it between threads is safe the same way `Stack` itself is.
+unsafe impl Send for MmapStack {}
+
 #[derive(PartialEq, Eq, Debug)]
+#[allow(clippy::upper_case_acronyms)]
 enum State {
     AVAIABLE,
     RUNNING,
     READY,
+    // Parked on a `recv()` with an empty mailbox. Distinct from `READY`
+    // so a blocked stack is neither re-run by the scheduler nor treated
+    // as reusable `AVAIABLE` memory - only `send_to` can move it back.
+    BLOCKED,
+    // Parked on a `stack_yield()`. Distinct from `READY`/`BLOCKED` so a
+    // yielded-but-not-finished stack is neither re-run by the scheduler
+    // nor treated as reusable `AVAIABLE` memory - only `resume` can move
+    // it back.
+    SUSPENDED,
 }
 
 #[derive(Debug, Default)]
@@ -41,233 +296,990 @@ struct StackContext {
     r12: u64,
     rbx: u64,
     rbp: u64,
-    edi: u64,
-    esi: u64,
+    // Full width, unlike the truncated `edi`/`esi` this replaced -
+    // `sigvtalrm_handler` can land mid-instruction with a real 64-bit
+    // pointer live in either one, and truncating would corrupt a pointer
+    // above 4GB on a cooperative resume.
+    rdi: u64,
+    rsi: u64,
+    // Caller-saved registers/flags a cooperative call never needs to
+    // carry, but a signal-preempted stack can resume through either path,
+    // so `switch_and_run` saves and restores these too. `rax` is round-
+    // tripped rather than used as scratch, so a live accumulator survives
+    // a preemption; `r11` is the one left out (see below).
+    rax: u64,
+    rcx: u64,
+    rdx: u64,
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    rflags: u64,
+    // Where to resume, stored explicitly rather than inferred from
+    // `[rsp]` - lets `sigvtalrm_handler` hand off a preempted context
+    // without writing a synthetic return address into the interrupted
+    // stack's SysV red zone.
+    rip: u64,
+    // Captured/restored by `sigvtalrm_handler` only - `sigreturn` restores
+    // every register atomically, so there's no scratch-register puzzle
+    // there. `switch_and_run`'s cooperative resume can't carry this one
+    // through: it needs one scratch register to stage `rip` through, and
+    // `r11` does that job - `syscall` already clobbers `rcx`/`r11` per the
+    // SysV ABI, so well-behaved code never keeps anything live in it
+    // across one, unlike `rax`. A stack preempted with a live, meaningful
+    // `r11` that's later resumed cooperatively won't get it back.
+    r11: u64,
 }
 
-#[derive(Debug, Default, Clone, Copy)]
-#[repr(C)]
-pub struct Event {
-    pair: (usize, usize),
-    data: usize,
-}
-
-#[derive(Debug)]
-#[repr(C)]
-enum ScheduleType {
-    RR,
-    O1(usize),
+/// A boxed, type-erased message. `send_to`/`recv` downcast it back to the
+/// caller's `T`, which is what lets the mailbox carry a generic message
+/// type without making every `Stack`/`Worker` generic over it.
+type Message = Box<dyn Any + Send>;
+
+/// What a single step of a generator-style coroutine reports to
+/// `stack_yield`. `Normal`/`Done` need no explicit variant: "keep
+/// running" is just not calling `stack_yield` at all, and "done" is
+/// falling off the end of the base function into `finish_and_next_stack`
+/// as every stack already does.
+pub enum SchedSignal<T> {
+    /// Suspend, surfacing `T` to whoever calls `resume` on this stack.
+    Yield(T),
 }
 
-#[derive(Debug)]
+/// Fixed-capacity ring buffer backing one stack's mailbox. Unlike the old
+/// `ShareBuffer`, whose `avaiable_index` only ever grew, `head`/`tail`
+/// wrap and every popped slot is immediately reusable.
 struct ShareBuffer {
-    data_pool: Vec<Event>,
-    avaiable_index: usize,
+    slots: Vec<Option<Message>>,
+    capacity: usize,
+    head: usize,
+    tail: usize,
+    len: usize,
 }
 
 impl ShareBuffer {
-    fn new() -> ShareBuffer {
-        let data_pool = Vec::<Event>::with_capacity(DATA_SHARE_BUFFER_SIZE);
+    fn new(capacity: usize) -> ShareBuffer {
+        let mut slots = Vec::with_capacity(capacity);
+        slots.resize_with(capacity, || None);
         ShareBuffer {
-            data_pool,
-            avaiable_index: 0,
+            slots,
+            capacity,
+            head: 0,
+            tail: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, msg: Message) -> bool {
+        if self.len == self.capacity {
+            return false;
+        }
+        self.slots[self.tail] = Some(msg);
+        self.tail = (self.tail + 1) % self.capacity;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        if self.len == 0 {
+            return None;
+        }
+        let msg = self.slots[self.head].take();
+        self.head = (self.head + 1) % self.capacity;
+        self.len -= 1;
+        msg
+    }
+}
+
+/// A stack's mailbox: its message queue plus, if it is currently parked on
+/// an empty `recv()`, the parked `Stack` itself. Bundling both under one
+/// lock is what makes `send_to`/`recv` race-free - `push` and "is anyone
+/// parked" must be observed atomically, or a message pushed between a
+/// receiver's empty-check and it actually parking would sit forever with
+/// no one left to wake it up. Boxed so the table can rehash without
+/// invalidating the `StackContext` pointer `block_on_mailbox` hands to
+/// `switch_and_run`.
+struct Mailbox {
+    buffer: ShareBuffer,
+    parked: Option<Box<Stack>>,
+}
+
+impl Mailbox {
+    fn new(capacity: usize) -> Mailbox {
+        Mailbox {
+            buffer: ShareBuffer::new(capacity),
+            parked: None,
+        }
+    }
+}
+
+/// Per-stack mailboxes, keyed by `stack_id` rather than embedded in
+/// `Stack` itself: a stack's mailbox must be reachable from whichever
+/// worker happens to be sending to it, regardless of whether the target
+/// is currently `RUNNING` on some other worker or sitting `READY` in a
+/// `ChaseLevDeque`.
+fn mailboxes() -> &'static Mutex<HashMap<usize, Mailbox>> {
+    static CELL: OnceLock<Mutex<HashMap<usize, Mailbox>>> = OnceLock::new();
+    CELL.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Stacks parked on a `stack_yield`, keyed by `stack_id` the same way a
+/// `recv()`-parked stack lives inside its own `Mailbox` - boxed so `resume`
+/// can read `yielded` back out (and so the table can rehash) without
+/// invalidating the `StackContext` pointer the generator-resume switch
+/// saves into.
+fn suspended_stacks() -> &'static Mutex<HashMap<usize, Box<Stack>>> {
+    static CELL: OnceLock<Mutex<HashMap<usize, Box<Stack>>>> = OnceLock::new();
+    CELL.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Every worker's local run queue, so `send_to` can re-park a woken
+/// `BLOCKED` stack even from outside any worker thread. A `Mutex`, not a
+/// `OnceLock`, since `run`/`run_preemptive` can run more than once per
+/// process and each run needs its own queues picked up.
+fn all_queues() -> &'static Mutex<Vec<Arc<ChaseLevDeque>>> {
+    static CELL: OnceLock<Mutex<Vec<Arc<ChaseLevDeque>>>> = OnceLock::new();
+    CELL.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Returned by `send_to` when `id`'s mailbox is full, carrying the message
+/// back instead of dropping it - mirrors `std::sync::mpsc::SendError<T>`.
+pub struct SendError<T>(pub T);
+
+impl<T> std::fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+impl<T> std::fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("sending on a full mailbox")
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+/// Sends `msg` to stack `id`'s mailbox and, if that stack was parked in
+/// `recv()`, moves it back to `READY` and parks it on a run queue. Mirrors
+/// `std::sync::mpsc::Sender::send`: a full mailbox hands `msg` back in
+/// `Err` rather than dropping it.
+///
+/// Push and wake happen under a single lock on `mailboxes()` so a message
+/// can never land in the window between a receiver finding its mailbox
+/// empty and actually parking itself.
+pub fn send_to<T: Any + Send>(id: usize, msg: T) -> Result<(), SendError<T>> {
+    let mut mailboxes = mailboxes().lock().unwrap();
+    let mailbox = mailboxes.entry(id).or_insert_with(|| Mailbox::new(MAILBOX_CAPACITY));
+    // Checked ahead of the push so a full mailbox can hand `msg` back unboxed.
+    if mailbox.buffer.len == mailbox.buffer.capacity {
+        drop(mailboxes);
+        return Err(SendError(msg));
+    }
+    assert!(mailbox.buffer.push(Box::new(msg)));
+    let woken = mailbox.parked.take();
+    drop(mailboxes);
+
+    if let Some(mut stack) = woken {
+        stack.state = State::READY;
+        park_on_a_run_queue(*stack);
+    }
+    Ok(())
+}
+
+fn park_on_a_run_queue(stack: Stack) {
+    let worker_ptr = WORKER_PTR.with(|p| p.get());
+    if !worker_ptr.is_null() {
+        unsafe { (*worker_ptr).local_queue.push_bottom(stack) };
+        return;
+    }
+    // Called from outside any worker thread (e.g. the OS-thread that
+    // called `run()`): fall straight onto the first worker's queue and
+    // let work-stealing redistribute it from there.
+    if let Some(queue) = all_queues().lock().unwrap().first() {
+        queue.push_bottom(stack);
+    }
+}
+
+/// Blocks the calling stack until a message of type `T` arrives in its
+/// own mailbox, parking it (state -> `BLOCKED`) and switching away in
+/// the meantime instead of busy-waiting.
+pub fn recv<T: Any + Send>() -> T {
+    loop {
+        let id = get_current_stack_id();
+        let mut mailboxes = mailboxes().lock().unwrap();
+        let mailbox = mailboxes.entry(id).or_insert_with(|| Mailbox::new(MAILBOX_CAPACITY));
+        if let Some(msg) = mailbox.buffer.pop() {
+            drop(mailboxes);
+            return *msg
+                .downcast::<T>()
+                .expect("mailbox message type did not match recv::<T>()");
         }
+
+        // Still empty: hand the held lock straight to `block_on_mailbox`
+        // so parking ourselves and the empty-check above happen as one
+        // atomic step - otherwise a `send_to` landing in between could
+        // push a message that nobody is left to deliver a wakeup for.
+        let worker_ptr = WORKER_PTR.with(|p| p.get());
+        unsafe { (*worker_ptr).block_on_mailbox(id, mailboxes) };
     }
 }
 
 impl Stack {
-    fn new(stack_id: usize, state: State) -> Self {
+    /// Grows the pool by mmapping a fresh buffer whenever `free_buffers()`
+    /// has nothing reusable cached, same as any watermark buffer pool;
+    /// only panics once `max_stacks` (set via `LeydiStacks::with_capacity`)
+    /// live stacks are already outstanding - a real ceiling on concurrency,
+    /// not a reflection of how much backing memory happens to be cached.
+    fn new(stack_id: usize, state: State, guard_pages: usize) -> Self {
+        let stats = pool_stats();
+        let ceiling = stats.max_stacks.load(Ordering::Relaxed);
+        let live = stats.current.fetch_add(1, Ordering::Relaxed) + 1;
+        if live > ceiling {
+            stats.current.fetch_sub(1, Ordering::Relaxed);
+            panic!("No avaiable stack slot: pool ceiling of {} reached", ceiling);
+        }
+        stats.peak.fetch_max(live, Ordering::Relaxed);
+
+        let stack_buffer = take_free_buffer(guard_pages).unwrap_or_else(|| MmapStack::new(guard_pages));
+        register_stack_region(stack_id, stack_buffer.base_ptr as usize, stack_buffer.guard_bytes);
+
         Stack {
             stack_id,
-            stack_buffer: vec![0_u8; STACK_BUFFER_SIZE],
+            stack_buffer,
             stack_context: StackContext::default(),
             state,
+            yielded: None,
+            resume_into: None,
+            entry_fn: None,
         }
     }
 }
 
-impl LeydiStacks {
-    #[cfg(target_arch = "x86_64")]
-    pub fn new() -> Self {
-        let process_main_stack = Stack::new(PROCESS_MAIN_STACK_ID, State::RUNNING);
+impl Drop for Stack {
+    /// Recycles this stack's buffer into `free_buffers()` (or lets it
+    /// really unmap past the high watermark) instead of always munmapping,
+    /// so the next `Stack::new` can skip straight past the mmap syscall.
+    fn drop(&mut self) {
+        let stats = pool_stats();
+        let live = stats.current.fetch_sub(1, Ordering::Relaxed) - 1;
+
+        let buffer = std::mem::replace(&mut self.stack_buffer, MmapStack::invalid());
+        if !buffer.base_ptr.is_null() {
+            deregister_stack_region(buffer.base_ptr as usize);
+            recycle_or_release(buffer);
+        }
 
-        let mut stack_pool = Vec::with_capacity(MAX_STACKS);
-        stack_pool.push(process_main_stack);
+        if live < stats.low_watermark.load(Ordering::Relaxed) {
+            release_idle_buffers();
+        }
+    }
+}
 
-        let mut avaiable_stacks: Vec<Stack> = (1..=MAX_STACKS)
-            .map(|i| Stack::new(i, State::AVAIABLE))
-            .collect();
-        stack_pool.append(&mut avaiable_stacks);
+/// Bounded lock-free work-stealing deque (Chase-Lev). The owning worker
+/// pushes/pops its own end LIFO for cache locality; any other worker may
+/// steal from the opposite end FIFO, which is what lets an idle worker
+/// pull `READY` stacks off a busy worker's queue without a lock.
+struct ChaseLevDeque {
+    buffer: UnsafeCell<Vec<Option<Stack>>>,
+    capacity: usize,
+    top: AtomicUsize,
+    bottom: AtomicUsize,
+    // Owner-only spillover for when the fixed-size ring above fills up.
+    // Replaces the old hard cap - a worker that ends up owning more than
+    // `capacity` READY stacks grows into here instead of `push_bottom`
+    // silently wrapping and clobbering not-yet-popped slots.
+    overflow: Mutex<Vec<Stack>>,
+}
 
-        LeydiStacks {
-            stack_pool,
-            curr_stack_id: PROCESS_MAIN_STACK_ID,
-            data_buffer: ShareBuffer::new(),
+// Safety: `top`/`bottom` are the only shared-mutable-state guards into
+// `buffer`; `push_bottom`/`pop_bottom` are owner-only, `steal` uses a CAS
+// on `top` to arbitrate against concurrent thieves (and the owner).
+// `overflow` is plain `Mutex`-guarded, so it needs no such reasoning.
+unsafe impl Sync for ChaseLevDeque {}
+
+impl ChaseLevDeque {
+    fn new(capacity: usize) -> Self {
+        let mut buffer = Vec::with_capacity(capacity);
+        for _ in 0..capacity {
+            buffer.push(None);
+        }
+        ChaseLevDeque {
+            buffer: UnsafeCell::new(buffer),
+            capacity,
+            top: AtomicUsize::new(0),
+            bottom: AtomicUsize::new(0),
+            overflow: Mutex::new(Vec::new()),
         }
     }
 
-    pub fn run(&mut self) -> () {
+    /// Owner-only. Parks a `READY` stack at the bottom (LIFO) end, or in
+    /// `overflow` once the ring is full.
+    fn push_bottom(&self, stack: Stack) {
+        let b = self.bottom.load(Ordering::Relaxed);
+        let t = self.top.load(Ordering::Acquire);
+        if b - t >= self.capacity {
+            self.overflow.lock().unwrap().push(stack);
+            return;
+        }
+        let slot = b % self.capacity;
         unsafe {
-            RUNTIME_PTR = self as *mut LeydiStacks;
+            (&mut *self.buffer.get())[slot] = Some(stack);
         }
-        while self.switch_stack(ScheduleType::RR) {}
+        self.bottom.store(b + 1, Ordering::Release);
     }
 
-    #[inline(never)]
-    fn switch_stack(&mut self, schedule_type: ScheduleType) -> bool {
-        let mut ready_stack_id = 0 as usize;
-
-        match schedule_type {
-            ScheduleType::RR => {
-                // Get a READY stack id
-                while self.stack_pool[ready_stack_id].state != State::READY {
-                    ready_stack_id += 1;
-                    if ready_stack_id == MAX_STACKS {
-                        ready_stack_id = 0;
-                    }
-                    if ready_stack_id == self.curr_stack_id {
-                        return false;
-                    }
-                }
-            }
-            ScheduleType::O1(id) => {
-                ready_stack_id = id;
+    /// Owner-only. Pops from the bottom (LIFO) end; races a concurrent
+    /// thief only when a single item remains. Falls back to `overflow`
+    /// once the ring reports empty.
+    fn pop_bottom(&self) -> Option<Stack> {
+        let b = self.bottom.load(Ordering::Relaxed);
+        if b == self.top.load(Ordering::Acquire) {
+            return self.overflow.lock().unwrap().pop();
+        }
+        let new_b = b - 1;
+        self.bottom.store(new_b, Ordering::Relaxed);
+        // Forbids the StoreLoad reordering this Dekker-style check depends
+        // on, pairing with the thief's `SeqCst` `top.compare_exchange` below.
+        fence(Ordering::SeqCst);
+        let slot = new_b % self.capacity;
+        let slot_ptr =
+            unsafe { (&mut *self.buffer.get()).get_unchecked_mut(slot) as *mut Option<Stack> };
+        let candidate = unsafe { std::ptr::read(slot_ptr) };
+
+        let t = self.top.load(Ordering::Acquire);
+        if new_b > t {
+            // Uncontested: no thief can be racing this slot, so it's safe
+            // to blank it out now - the next `push_bottom` into the same
+            // slot must never see (and drop) the stale copy `ptr::read`
+            // above left behind.
+            unsafe { std::ptr::write(slot_ptr, None) };
+            return candidate;
+        }
+        if new_b == t {
+            let won = self
+                .top
+                .compare_exchange(t, t + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok();
+            self.bottom.store(b, Ordering::Relaxed);
+            if won {
+                // Same as above: we own this slot outright now, so clear
+                // it before handing `candidate` back.
+                unsafe { std::ptr::write(slot_ptr, None) };
+                return candidate;
             }
+            // Lost the race to a concurrent `steal()`, which already wrote
+            // its own `None` back after reading the same bytes - ours is
+            // just a stale copy of a value someone else now owns.
+            std::mem::forget(candidate);
+            return None;
         }
+        self.bottom.store(b, Ordering::Relaxed);
+        std::mem::forget(candidate);
+        None
+    }
 
-        // set ready stack state READY to RUNNING
-        self.stack_pool[ready_stack_id].state = State::RUNNING;
-
-        // set current stack RUNNING to READY
-        if self.stack_pool[self.curr_stack_id].state != State::AVAIABLE {
-            self.stack_pool[self.curr_stack_id].state = State::READY;
+    /// Any thread. Steals from the top (FIFO) end, leaving the owner's
+    /// LIFO end undisturbed. Falls back to `overflow` once the ring
+    /// reports empty, since a stack parked there is just as steal-able.
+    fn steal(&self) -> Option<Stack> {
+        let t = self.top.load(Ordering::Acquire);
+        let b = self.bottom.load(Ordering::Acquire);
+        if t >= b {
+            return self.overflow.lock().unwrap().pop();
         }
+        let slot = t % self.capacity;
+        let slot_ptr =
+            unsafe { (&mut *self.buffer.get()).get_unchecked_mut(slot) as *mut Option<Stack> };
+        let candidate = unsafe { std::ptr::read(slot_ptr) };
+
+        if self
+            .top
+            .compare_exchange(t, t + 1, Ordering::SeqCst, Ordering::Relaxed)
+            .is_ok()
+        {
+            unsafe { std::ptr::write(slot_ptr, None) };
+            candidate
+        } else {
+            std::mem::forget(candidate);
+            None
+        }
+    }
+}
 
-        let paused_stack_id = self.curr_stack_id;
-        self.curr_stack_id = ready_stack_id;
+/// One OS worker thread's scheduling state: its own run queue plus
+/// handles to every sibling worker's queue to steal from when idle.
+///
+/// Invariant: a `RUNNING` stack is owned exclusively by the worker that
+/// switched to it - it lives in `curr_stack`, not in any `ChaseLevDeque` -
+/// and cannot be stolen until it yields back to `READY` and is pushed
+/// onto `local_queue` again.
+struct Worker {
+    #[allow(dead_code)]
+    worker_id: usize,
+    host_context: StackContext,
+    curr_stack: Option<Stack>,
+    local_queue: Arc<ChaseLevDeque>,
+    siblings: Vec<Arc<ChaseLevDeque>>,
+    /// A finished stack that `finish_current_and_advance` has switched
+    /// away from but not yet dropped - see that function for why the
+    /// drop has to wait until we're running on different memory.
+    zombie: Option<Stack>,
+}
 
+impl Worker {
+    fn find_next(&self) -> Option<Stack> {
+        if let Some(stack) = self.local_queue.pop_bottom() {
+            return Some(stack);
+        }
+        // Nothing local - try to steal a READY stack from a busy sibling,
+        // starting at a pseudo-random offset so workers don't all hammer
+        // the same victim.
+        let start = self.worker_id.wrapping_mul(2654435761);
+        for i in 0..self.siblings.len() {
+            let victim = &self.siblings[(start + i) % self.siblings.len().max(1)];
+            if let Some(stack) = victim.steal() {
+                return Some(stack);
+            }
+        }
+        None
+    }
+
+    /// Switches back to this worker's native context, abandoning whatever
+    /// is in `curr_stack` (left there to be dropped normally, the same as
+    /// any other finished stack, once the worker itself drops). Only
+    /// meant to be called from `finish_and_next_stack`'s `None` branch,
+    /// where `curr_stack` just finished and `find_next` came up empty -
+    /// see the comment there for why this can't simply be folded into
+    /// `schedule_next` itself.
+    #[inline(never)]
+    fn switch_to_host(&mut self) {
+        IN_SWITCH.with(|f| f.set(true));
         unsafe {
-            let paused_stack_context: *mut StackContext =
-                &mut self.stack_pool[paused_stack_id].stack_context;
+            let paused_stack_context: *mut StackContext = &mut self
+                .curr_stack
+                .as_mut()
+                .expect("switch_to_host with no stack running")
+                .stack_context;
+            let host_context: *const StackContext = &self.host_context;
+            asm!("call switch_and_run", in("rdi") paused_stack_context, in("rsi") host_context, clobber_abi("C"));
+        }
+        IN_SWITCH.with(|f| f.set(false));
+    }
+
+    /// Pop (locally, or via steal) the next `READY` stack and switch into
+    /// it, parking whatever was running before. Returns `false` once
+    /// there is nothing left to run anywhere, so the worker's loop ends
+    /// and the OS thread exits.
+    #[inline(never)]
+    fn schedule_next(&mut self) -> bool {
+        // Set before `find_next`, not just around the asm switch below -
+        // `find_next`/`push_bottom` take `ChaseLevDeque::overflow`'s
+        // non-reentrant `Mutex`, so a SIGVTALRM tick landing mid-lock
+        // must see the guard already up and defer itself, rather than
+        // re-entering `sigvtalrm_handler` and self-deadlocking on it.
+        IN_SWITCH.with(|f| f.set(true));
+
+        let mut next = match self.find_next() {
+            Some(stack) => stack,
+            None => {
+                IN_SWITCH.with(|f| f.set(false));
+                return false;
+            }
+        };
+        next.state = State::RUNNING;
+
+        let paused = self.curr_stack.take();
+        self.curr_stack = Some(next);
 
+        unsafe {
+            let paused_stack_context: *mut StackContext = match &paused {
+                Some(stack) => &stack.stack_context as *const _ as *mut _,
+                None => &mut self.host_context,
+            };
             let ready_stack_context: *const StackContext =
-                &self.stack_pool[ready_stack_id].stack_context;
+                &self.curr_stack.as_ref().unwrap().stack_context;
 
             asm!("call switch_and_run", in("rdi") paused_stack_context, in("rsi") ready_stack_context, clobber_abi("C"));
         }
+
+        // Control resumes here once something switches back to whichever
+        // context we just paused. Re-park it if it is still runnable.
+        if let Some(stack) = paused {
+            if stack.state == State::READY {
+                self.local_queue.push_bottom(stack);
+            }
+        }
+
+        IN_SWITCH.with(|f| f.set(false));
         true
     }
 
-    pub fn new_stack(&mut self, base_function: fn(), trigger_function: fn(usize, usize)) {
-        let new_stack = self
-            .stack_pool
-            .iter_mut()
-            .find(|t| t.state == State::AVAIABLE)
-            .expect("No avaiable stack found in pool.");
+    /// Like `schedule_next`, but for a `curr_stack` that has just finished
+    /// and will never be resumed (`finish_and_next_stack`'s `None` branch).
+    /// `schedule_next` parks the outgoing stack in a local `paused` that
+    /// lives on *its own* buffer, and only drops it once something
+    /// switches back to the context it was saved into - which never
+    /// happens for a stack nobody references anymore, leaking it (and its
+    /// guard-page registration) permanently.
+    ///
+    /// We can't just drop it in place before switching away either: that
+    /// would `munmap` the very memory this function's own frame (and the
+    /// `asm!` call below) is still executing on. Instead it's stashed in
+    /// `self.zombie` - a `Worker` field, so it lives off any coroutine's
+    /// buffer - and reclaimed at the top of the next call, by which point
+    /// we're safely running on different memory.
+    #[inline(never)]
+    fn finish_current_and_advance(&mut self) -> bool {
+        IN_SWITCH.with(|f| f.set(true));
+
+        // Dropping the previous zombie runs `Stack::drop`, which takes
+        // `STACK_REGIONS`'s and the pool's `free_buffers()` mutex - both
+        // non-reentrant - so it must happen under the same `IN_SWITCH` guard
+        // as everything else that reaches those locks.
+        self.zombie = None;
+
+        let mut next = match self.find_next() {
+            Some(stack) => stack,
+            None => {
+                IN_SWITCH.with(|f| f.set(false));
+                return false;
+            }
+        };
+        next.state = State::RUNNING;
 
-        // set stack as READY
-        new_stack.state = State::READY;
+        self.zombie = self.curr_stack.take();
+        self.curr_stack = Some(next);
 
+        let zombie_stack_context: *mut StackContext = &mut self
+            .zombie
+            .as_mut()
+            .expect("finish_current_and_advance with no finished stack")
+            .stack_context;
         unsafe {
-            let stack_buff_ptr = new_stack
-                .stack_buffer
-                .as_mut_ptr()
-                .offset(new_stack.stack_buffer.len() as isize);
-
-            let stack_buff_ptr = (stack_buff_ptr as usize & !15) as *mut u8;
+            let ready_stack_context: *const StackContext =
+                &self.curr_stack.as_ref().unwrap().stack_context;
+            asm!("call switch_and_run", in("rdi") zombie_stack_context, in("rsi") ready_stack_context, clobber_abi("C"));
+        }
 
-            let mut _buffer_index: *mut u64 = 0 as *mut u64;
+        IN_SWITCH.with(|f| f.set(false));
+        true
+    }
 
-            //**************Event Flow***************/
-            _buffer_index = stack_buff_ptr.offset(-16) as *mut u64;
-            std::ptr::write(_buffer_index, finish_and_next_stack as u64);
+    /// Drops every stack still parked on this worker's local queue and
+    /// switches back to the worker's native (host) context, ending this
+    /// worker's scheduling loop.
+    #[inline(never)]
+    fn terminate(&mut self) -> bool {
+        // Set before the drain loop, not just around the asm switch - see
+        // `schedule_next` for why `pop_bottom`'s locking needs the guard
+        // up too.
+        IN_SWITCH.with(|f| f.set(true));
+
+        while self.local_queue.pop_bottom().is_some() {}
+
+        let running = match self.curr_stack.take() {
+            Some(stack) => stack,
+            None => {
+                IN_SWITCH.with(|f| f.set(false));
+                return false;
+            }
+        };
 
-            _buffer_index = stack_buff_ptr.offset(-24) as *mut u64;
-            std::ptr::write(_buffer_index, func_return as u64);
+        unsafe {
+            let paused_stack_context: *mut StackContext =
+                &running.stack_context as *const _ as *mut _;
+            let host_context: *const StackContext = &self.host_context;
+            asm!("call switch_and_run", in("rdi") paused_stack_context, in("rsi") host_context, clobber_abi("C"));
+        }
+        IN_SWITCH.with(|f| f.set(false));
+        false
+    }
 
-            _buffer_index = stack_buff_ptr.offset(-32) as *mut u64;
-            std::ptr::write(_buffer_index, trigger_function as u64);
+    /// Switches directly to a specific id, but only among stacks this
+    /// worker already owns (its current stack or its own local queue) -
+    /// a stack parked on another worker's queue cannot be targeted this
+    /// way, since ownership of `READY` stacks is per-worker until stolen.
+    #[inline(never)]
+    fn switch_stack_to(&mut self, stack_id: usize) -> bool {
+        if let Some(curr) = &self.curr_stack {
+            if curr.stack_id == stack_id {
+                eprintln!("Stack:{} already running..", stack_id);
+                return false;
+            }
+        }
 
-            _buffer_index = stack_buff_ptr.offset(-40) as *mut u64;
-            std::ptr::write(_buffer_index, func_return as u64);
+        // Set before the drain loop below, not just around the asm switch
+        // - see `schedule_next` for why `pop_bottom`/`push_bottom`'s
+        // locking needs the guard up too.
+        IN_SWITCH.with(|f| f.set(true));
+
+        let mut drained = Vec::new();
+        let mut target = None;
+        while let Some(stack) = self.local_queue.pop_bottom() {
+            if stack.stack_id == stack_id {
+                target = Some(stack);
+            } else {
+                drained.push(stack);
+            }
+        }
+        for stack in drained {
+            self.local_queue.push_bottom(stack);
+        }
 
-            //**************Event Flow***************/
-            _buffer_index = stack_buff_ptr.offset(-48) as *mut u64;
-            std::ptr::write(_buffer_index, finish_and_next_stack as u64);
+        match target {
+            Some(mut stack) => {
+                stack.state = State::RUNNING;
+                let paused = self.curr_stack.take();
+                self.curr_stack = Some(stack);
+
+                unsafe {
+                    let paused_stack_context: *mut StackContext = match &paused {
+                        Some(stack) => &stack.stack_context as *const _ as *mut _,
+                        None => &mut self.host_context,
+                    };
+                    let ready_stack_context: *const StackContext =
+                        &self.curr_stack.as_ref().unwrap().stack_context;
+                    asm!("call switch_and_run", in("rdi") paused_stack_context, in("rsi") ready_stack_context, clobber_abi("C"));
+                }
 
-            _buffer_index = stack_buff_ptr.offset(-56) as *mut u64;
-            std::ptr::write(_buffer_index, func_return as u64);
+                if let Some(stack) = paused {
+                    if stack.state == State::READY {
+                        self.local_queue.push_bottom(stack);
+                    }
+                }
+                IN_SWITCH.with(|f| f.set(false));
+                true
+            }
+            None => {
+                IN_SWITCH.with(|f| f.set(false));
+                eprintln!("Stack:{} not found on this worker.", stack_id);
+                false
+            }
+        }
+    }
 
-            _buffer_index = stack_buff_ptr.offset(-64) as *mut u64;
-            std::ptr::write(_buffer_index, base_function as u64);
+    /// Parks the running stack inside its own `Mailbox` entry (state ->
+    /// `BLOCKED`) and switches to the next runnable stack, same as
+    /// `schedule_next` except the paused stack is never re-parked on
+    /// `local_queue` - only a matching `send_to` can make it `READY`
+    /// again, at which point it is pushed onto some worker's queue like
+    /// any other newly-readied stack. `mailboxes` is the lock `recv`
+    /// already holds after finding its mailbox empty - taking it here
+    /// instead of re-acquiring it is what makes the empty-check and the
+    /// parking atomic, so a concurrent `send_to` can never push a message
+    /// into the gap between the two and have no one left to deliver a
+    /// wakeup.
+    #[inline(never)]
+    fn block_on_mailbox(&mut self, id: usize, mut mailboxes: MutexGuard<HashMap<usize, Mailbox>>) {
+        // Set before `find_next` below is ever called, not just around
+        // the asm switch - see `schedule_next` for why its locking needs
+        // the guard up too.
+        IN_SWITCH.with(|f| f.set(true));
+
+        let mut blocked = self
+            .curr_stack
+            .take()
+            .expect("recv() called with no stack currently running");
+        blocked.state = State::BLOCKED;
+
+        // Boxed so its address (and thus the `StackContext` pointer the
+        // asm switch below saves into) stays stable even if the table
+        // storing it rehashes while we're parked.
+        let boxed = Box::new(blocked);
+        let paused_stack_context: *mut StackContext = &boxed.stack_context as *const _ as *mut _;
+        mailboxes.get_mut(&id).unwrap().parked = Some(boxed);
+        drop(mailboxes);
+
+        // Keep looking (locally, then by stealing) for something else to
+        // run. A short sleep avoids pegging the CPU while every worker
+        // is briefly idle waiting on the same `send_to`.
+        let mut next = self.find_next();
+        while next.is_none() {
+            thread::sleep(Duration::from_micros(200));
+            next = self.find_next();
+        }
+        let mut next = next.unwrap();
+        next.state = State::RUNNING;
+        self.curr_stack = Some(next);
 
-            new_stack.stack_context.rsp = stack_buff_ptr.offset(-64) as *mut u64 as u64;
+        unsafe {
+            let ready_stack_context: *const StackContext =
+                &self.curr_stack.as_ref().unwrap().stack_context;
+            asm!("call switch_and_run", in("rdi") paused_stack_context, in("rsi") ready_stack_context, clobber_abi("C"));
         }
+        IN_SWITCH.with(|f| f.set(false));
     }
+}
 
-    #[inline(never)]
-    fn terminate_stacks(&mut self) -> bool {
-        // make all stacks AVAIABLE
-        for stack in &mut self.stack_pool {
-            stack.state = State::AVAIABLE;
+fn prepare_stack_entry(stack: &mut Stack, base_function: fn()) {
+    unsafe {
+        let stack_buff_ptr = stack.stack_buffer.as_mut_ptr().add(stack.stack_buffer.len());
+
+        let stack_buff_ptr = (stack_buff_ptr as usize & !15) as *mut u8;
+
+        let mut _buffer_index: *mut u64 = std::ptr::null_mut::<u64>();
+
+        _buffer_index = stack_buff_ptr.offset(-16) as *mut u64;
+        std::ptr::write(_buffer_index, finish_and_next_stack as *const () as u64);
+
+        _buffer_index = stack_buff_ptr.offset(-24) as *mut u64;
+        std::ptr::write(_buffer_index, func_return as *const () as u64);
+
+        // `rip` points at `stack_entry_trampoline`, not straight at
+        // `base_function` - see its doc comment for why. It's entered via
+        // `switch_and_run`'s explicit `rip` jump (see `StackContext::rip`),
+        // not a planted stack cell - only what it *returns into*
+        // (`func_return`, then `finish_and_next_stack`) needs to sit on the
+        // stack, since those are reached by its own compiler-generated
+        // `ret` once `base_function` has returned to it in turn.
+        stack.entry_fn = Some(base_function);
+        stack.stack_context.rip = stack_entry_trampoline as *const () as u64;
+        stack.stack_context.rsp = stack_buff_ptr.offset(-24) as *mut u64 as u64;
+    }
+}
+
+/// What a fresh stack's `rip` actually points at, instead of its
+/// `base_function` directly. Every other landing spot lands right after
+/// that site's own `asm!("call switch_and_run")`, which already clears
+/// `IN_SWITCH` the instant it starts running again. A brand-new stack has
+/// no such landing spot - `switch_and_run` jumps straight to `rip`, no
+/// `call` to return from - so this is that missing spot: look up
+/// `base_function`, clear the guard, then make the same call
+/// `base_function` itself would have been entered with.
+///
+/// The lookup runs before the guard clears, not after: `switch_and_run`'s
+/// cooperative resume doesn't round-trip `r11` (see `StackContext`), so a
+/// tick landing mid-lookup could hand this pointer back corrupted on a
+/// later cooperative resume. Keeping `IN_SWITCH` set here makes
+/// `sigvtalrm_handler` defer that tick instead, same as it already does
+/// for one landing mid-`find_next`.
+#[no_mangle]
+extern "C" fn stack_entry_trampoline() {
+    let worker_ptr = WORKER_PTR.with(|p| p.get());
+    let base_function = unsafe {
+        (*worker_ptr)
+            .curr_stack
+            .as_ref()
+            .expect("stack_entry_trampoline called with no stack running")
+            .entry_fn
+            .expect("stack_entry_trampoline reached without an entry_fn set")
+    };
+    IN_SWITCH.with(|f| f.set(false));
+    base_function();
+}
+
+impl LeydiStacks {
+    #[cfg(target_arch = "x86_64")]
+    pub fn new() -> Self {
+        let worker_count = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+
+        LeydiStacks {
+            worker_count,
+            seed_stacks: Vec::new(),
+            scheduler_interval: DEFAULT_SCHEDULER_INTERVAL,
+            guard_pages: DEFAULT_GUARD_PAGES,
+            initial_capacity: DEFAULT_INITIAL_CAPACITY,
         }
-        // set main stack as READY
-        self.stack_pool[PROCESS_MAIN_STACK_ID].state = State::READY;
-        return self.switch_stack(ScheduleType::RR);
     }
+}
 
-    #[inline(never)]
-    fn switch_stack_to(&mut self, stack_id: usize) -> bool {
-        if self.stack_pool[stack_id].state == State::RUNNING {
-            eprintln!("Stack:{} already running..", stack_id);
-            return false;
+#[cfg(target_arch = "x86_64")]
+impl Default for LeydiStacks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LeydiStacks {
+    /// Overrides the number of OS worker threads spawned by `run`/
+    /// `run_preemptive`. Defaults to the available parallelism.
+    pub fn with_worker_count(mut self, worker_count: usize) -> Self {
+        self.worker_count = worker_count.max(1);
+        self
+    }
+
+    /// Sizes each worker's run queue ring buffer to `initial` slots and
+    /// caps the total number of live stacks across the whole runtime at
+    /// `max`. Unlike the old fixed `MAX_STACKS`, `initial` is only a
+    /// sizing hint: a worker that ends up owning more than `initial`
+    /// `READY` stacks spills into `ChaseLevDeque::overflow` instead of
+    /// corrupting its ring, right up until the `max` ceiling is hit, at
+    /// which point `Stack::new` panics. `initial` also doubles as the
+    /// high watermark for `free_buffers()` (with half of it as the low
+    /// watermark) - the same concurrency estimate that sizes the run
+    /// queue is a reasonable guess at how many idle buffers are worth
+    /// keeping mmap'd for reuse. Defaults to `DEFAULT_INITIAL_CAPACITY` /
+    /// unbounded if never called.
+    pub fn with_capacity(mut self, initial: usize, max: usize) -> Self {
+        self.initial_capacity = initial.max(1);
+        let stats = pool_stats();
+        stats
+            .max_stacks
+            .store(max.max(self.initial_capacity), Ordering::Relaxed);
+        stats
+            .high_watermark
+            .store(self.initial_capacity, Ordering::Relaxed);
+        stats
+            .low_watermark
+            .store(self.initial_capacity / 2, Ordering::Relaxed);
+        self
+    }
+
+    /// Current vs. peak number of live `Stack`s across the whole runtime,
+    /// plus how many idle buffers `free_buffers()` is currently holding
+    /// onto for reuse.
+    pub fn stats() -> PoolUsage {
+        let stats = pool_stats();
+        PoolUsage {
+            current: stats.current.load(Ordering::Relaxed),
+            peak: stats.peak.load(Ordering::Relaxed),
+            idle: free_buffers().lock().unwrap().len(),
         }
-        return self.switch_stack(ScheduleType::O1(stack_id));
     }
 
-    #[inline(never)]
-    fn trigger_stack_func(&mut self, target_stack_id: usize, event: Event) -> bool {
-        unsafe {
-            if target_stack_id <= PROCESS_MAIN_STACK_ID || target_stack_id > MAX_STACKS {
-                eprintln!("Wrong stack ID!");
-                return false;
-            }
-            let mut target_stack = &mut self.stack_pool[target_stack_id];
+    /// Sets how many `PROT_NONE` guard pages are placed below each
+    /// stack's usable buffer. Touching one raises SIGSEGV/SIGBUS instead
+    /// of silently corrupting adjacent memory, which is what makes it
+    /// safe to shrink `STACK_BUFFER_SIZE`.
+    pub fn with_guard_size(mut self, pages: usize) -> Self {
+        self.guard_pages = pages.max(1);
+        self
+    }
 
-            let mut stack_buff_ptr = target_stack
-                .stack_buffer
-                .as_mut_ptr()
-                .offset(target_stack.stack_buffer.len() as isize);
+    /// Sets the period of the virtual-timer tick used by `run_preemptive`.
+    /// Has no effect on cooperative scheduling via `run`.
+    pub fn with_scheduler_interval(mut self, interval: Duration) -> Self {
+        self.scheduler_interval = interval;
+        self
+    }
 
-            stack_buff_ptr = (stack_buff_ptr as usize & !15) as *mut u8;
+    pub fn new_stack(&mut self, base_function: fn()) {
+        self.seed_stacks.push(base_function);
+    }
 
-            target_stack.stack_context.rsp =
-                stack_buff_ptr.offset(TRIGGER_OFFSET) as *mut u64 as u64;
+    /// Spawns `worker_count` OS threads, each running its own
+    /// work-stealing scheduling loop, and blocks until every stack on
+    /// every worker has finished.
+    pub fn run(&mut self) {
+        self.spawn_workers(false)
+    }
 
-            self.data_buffer.data_pool.push(event);
+    /// Same M:N work-stealing runtime as `run`, but each worker also
+    /// installs its own SIGVTALRM handler/timer so its currently running
+    /// stack is preempted every `scheduler_interval` instead of depending
+    /// on cooperative `next_stack()` calls, modeled on the periodic
+    /// timer tick in APIC/timer-driven kernel schedulers.
+    #[cfg(target_arch = "x86_64")]
+    pub fn run_preemptive(&mut self) {
+        self.spawn_workers(true)
+    }
 
-            target_stack.stack_context.edi = self.curr_stack_id as u64;
-            target_stack.stack_context.esi = self.data_buffer.avaiable_index as u64;
+    fn spawn_workers(&mut self, preemptive: bool) {
+        let worker_count = self.worker_count;
+        let guard_pages = self.guard_pages;
+        let initial_capacity = self.initial_capacity;
+        let deques: Vec<Arc<ChaseLevDeque>> = (0..worker_count)
+            .map(|_| Arc::new(ChaseLevDeque::new(initial_capacity)))
+            .collect();
 
-            self.data_buffer.avaiable_index += 1;
+        // Stack ids are assigned globally (not per-worker) since `send_to`
+        // must be able to address any stack's mailbox regardless of which
+        // worker currently owns it.
+        //
+        // Seed stacks registered via `new_stack` are handed out round-robin
+        // across workers before any thread starts; work-stealing takes
+        // over once the workers are running.
+        for (i, base_function) in self.seed_stacks.drain(..).enumerate() {
+            let worker_idx = i % worker_count;
+            let stack_id = i + 1;
+            let mut stack = Stack::new(stack_id, State::READY, guard_pages);
+            prepare_stack_entry(&mut stack, base_function);
+            deques[worker_idx].push_bottom(stack);
+        }
+
+        *all_queues().lock().unwrap() = deques.clone();
+
+        let mut handles = Vec::with_capacity(worker_count);
+        for worker_id in 0..worker_count {
+            let local_queue = deques[worker_id].clone();
+            let siblings: Vec<Arc<ChaseLevDeque>> = deques
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != worker_id)
+                .map(|(_, d)| d.clone())
+                .collect();
+            let scheduler_interval = self.scheduler_interval;
+
+            handles.push(thread::spawn(move || {
+                let mut worker = Worker {
+                    worker_id,
+                    host_context: StackContext::default(),
+                    curr_stack: None,
+                    local_queue,
+                    siblings,
+                    zombie: None,
+                };
+                WORKER_PTR.with(|p| p.set(&mut worker as *mut Worker));
+
+                unsafe {
+                    install_sig_alt_stack();
+                    install_fault_handler();
+
+                    if preemptive {
+                        install_sigvtalrm_handler();
+                        arm_virtual_timer(scheduler_interval);
+                    }
+                }
+
+                while worker.schedule_next() {}
+            }));
+        }
+
+        for handle in handles {
+            let _ = handle.join();
         }
-        return self.switch_stack_to(target_stack_id);
     }
 }
 
-#[naked] // no prolouge & epilouge
+#[unsafe(naked)] // no prolouge & epilouge
 #[no_mangle]
+// Saves `rip` explicitly and `jmp`s to the target's instead of relying on
+// `ret`, so resuming never depends on a planted return address sitting in
+// the target stack's memory (see `StackContext::rip`).
 unsafe extern "C" fn switch_and_run() {
-    asm!(
-        "mov [rdi + 0x00], rsp",
+    naked_asm!(
+        // r11 stages `rip` through on both sides and is the one register not
+        // round-tripped - see `StackContext::r11`.
+        "pushfq",
+        "pop r11",
+        "mov [rdi + 0x78], r11",
+        "mov [rdi + 0x48], rax",
+        "mov [rdi + 0x50], rcx",
+        "mov [rdi + 0x58], rdx",
+        "mov [rdi + 0x60], r8",
+        "mov [rdi + 0x68], r9",
+        "mov [rdi + 0x70], r10",
+        "mov r11, [rsp]",
+        "mov [rdi + 0x80], r11",
+        "lea r11, [rsp + 8]",
+        "mov [rdi + 0x00], r11",
         "mov [rdi + 0x08], r15",
         "mov [rdi + 0x10], r14",
         "mov [rdi + 0x18], r13",
         "mov [rdi + 0x20], r12",
         "mov [rdi + 0x28], rbx",
         "mov [rdi + 0x30], rbp",
-        "mov [rdi + 0x38], edi",
-        "mov [rdi + 0x40], esi",
+        "mov [rdi + 0x38], rdi",
+        "mov [rdi + 0x40], rsi",
+        // Read rip before rsi (the base pointer) is clobbered below.
+        "mov r11, [rsi + 0x80]",
+        // popfq must land on the caller's stack, before rsp switches below -
+        // otherwise it clobbers 8 bytes of the target's red zone.
+        "mov rdi, [rsi + 0x78]",
+        "push rdi",
+        "popfq",
         "mov rsp, [rsi + 0x00]",
         "mov r15, [rsi + 0x08]",
         "mov r14, [rsi + 0x10]",
@@ -275,64 +1287,475 @@ unsafe extern "C" fn switch_and_run() {
         "mov r12, [rsi + 0x20]",
         "mov rbx, [rsi + 0x28]",
         "mov rbp, [rsi + 0x30]",
-        "mov edi, [rsi + 0x38]",
-        "mov esi, [rsi + 0x40]",
-        "ret",
-        options(noreturn)
+        "mov rax, [rsi + 0x48]",
+        "mov rcx, [rsi + 0x50]",
+        "mov rdx, [rsi + 0x58]",
+        "mov r8, [rsi + 0x60]",
+        "mov r9, [rsi + 0x68]",
+        "mov r10, [rsi + 0x70]",
+        "mov rdi, [rsi + 0x38]",
+        "mov rsi, [rsi + 0x40]",
+        "jmp r11",
     );
 }
 
-#[naked]
+#[unsafe(naked)]
 #[no_mangle]
 unsafe extern "C" fn func_return() {
-    asm!("ret", options(noreturn))
+    naked_asm!("ret")
+}
+
+/// Installs this thread's dedicated `sigaltstack`, shared by every signal
+/// handler registered with `SA_ONSTACK` below, so none of them ever run
+/// on (and potentially corrupt) whichever coroutine stack happens to be
+/// current when the signal fires.
+unsafe fn install_sig_alt_stack() {
+    SIG_ALT_STACK.with(|buf| {
+        let alt_stack = libc::stack_t {
+            ss_sp: buf.get() as *mut libc::c_void,
+            ss_flags: 0,
+            ss_size: SIG_ALT_STACK_SIZE,
+        };
+        if libc::sigaltstack(&alt_stack, std::ptr::null_mut()) != 0 {
+            panic!("sigaltstack failed: {}", std::io::Error::last_os_error());
+        }
+    });
+}
+
+/// Installs the SIGSEGV/SIGBUS handler that turns a guard-page touch into
+/// a clean "stack N overflowed" report instead of undefined corruption.
+unsafe fn install_fault_handler() {
+    let mut action: libc::sigaction = std::mem::zeroed();
+    action.sa_sigaction = fault_handler as *const () as usize;
+    action.sa_flags = libc::SA_ONSTACK | libc::SA_SIGINFO;
+    libc::sigemptyset(&mut action.sa_mask);
+
+    for signal in [libc::SIGSEGV, libc::SIGBUS] {
+        if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+            panic!("sigaction failed: {}", std::io::Error::last_os_error());
+        }
+    }
+}
+
+/// Fixed-capacity buffer implementing `fmt::Write`, so signal handlers can
+/// format a message without the heap allocation `format!`/`eprintln!`
+/// would do and without going through `Stderr`'s lock - both unsafe to
+/// touch from a handler that might have interrupted the very code
+/// holding them. `flush_to_stderr` writes it out with a single raw
+/// `write(2)`, which is signal-safe.
+struct SignalSafeBuf {
+    buf: [u8; 128],
+    len: usize,
+}
+
+impl SignalSafeBuf {
+    fn new() -> Self {
+        Self { buf: [0; 128], len: 0 }
+    }
+
+    fn flush_to_stderr(&self) {
+        unsafe {
+            libc::write(
+                libc::STDERR_FILENO,
+                self.buf.as_ptr() as *const libc::c_void,
+                self.len,
+            );
+        }
+    }
+}
+
+impl std::fmt::Write for SignalSafeBuf {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        let remaining = self.buf.len() - self.len;
+        let n = s.len().min(remaining);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// SIGSEGV/SIGBUS handler. Looks the fault address up in `STACK_REGIONS`
+/// (guard pages only, not the usable buffer past them) to recover the
+/// offending `stack_id`, reports it, and aborts cleanly - letting the
+/// process keep running after a corrupted stack is not safe.
+///
+/// Uses `try_lock` rather than `lock` on `STACK_REGIONS`: unlike
+/// `sigvtalrm_handler`'s locking (guarded by `IN_SWITCH`, since a
+/// dropped preemption tick just fires again later), a fault can land
+/// while this very thread already holds `STACK_REGIONS` - mid
+/// `register_stack_region`/`deregister_stack_region` in `Stack::new`/
+/// `drop` - and there is no "later" for a handler that doesn't return
+/// normally. Losing the lookup race just falls back to the generic
+/// message instead of hanging forever on a lock this thread itself
+/// holds.
+extern "C" fn fault_handler(
+    _sig: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _ctx: *mut libc::c_void,
+) {
+    use std::fmt::Write as _;
+
+    let fault_addr = unsafe { (*info).si_addr() } as usize;
+
+    if let Ok(regions) = STACK_REGIONS.try_lock() {
+        for &(stack_id, guard_base, guard_len) in regions.iter() {
+            if fault_addr >= guard_base && fault_addr < guard_base + guard_len {
+                let mut msg = SignalSafeBuf::new();
+                let _ = writeln!(msg, "stack {} overflowed (fault at {:#x})", stack_id, fault_addr);
+                msg.flush_to_stderr();
+                unsafe { libc::abort() };
+            }
+        }
+    }
+
+    let mut msg = SignalSafeBuf::new();
+    let _ = writeln!(
+        msg,
+        "segmentation fault at {:#x} (not inside a known stack guard page)",
+        fault_addr
+    );
+    msg.flush_to_stderr();
+    unsafe { libc::abort() };
+}
+
+/// Installs the SIGVTALRM handler on this thread's alternate signal
+/// stack so the preemption tick never runs on whichever coroutine stack
+/// happens to be current when the timer fires. Linux gives
+/// `setitimer`/SIGVTALRM thread semantics, so each worker arms and
+/// handles its own.
+#[cfg(target_arch = "x86_64")]
+unsafe fn install_sigvtalrm_handler() {
+    let mut action: libc::sigaction = std::mem::zeroed();
+    action.sa_sigaction = sigvtalrm_handler as *const () as usize;
+    action.sa_flags = libc::SA_ONSTACK | libc::SA_RESTART | libc::SA_SIGINFO;
+    libc::sigemptyset(&mut action.sa_mask);
+
+    if libc::sigaction(libc::SIGVTALRM, &action, std::ptr::null_mut()) != 0 {
+        panic!("sigaction failed: {}", std::io::Error::last_os_error());
+    }
+}
+
+/// Arms `ITIMER_VIRTUAL` to fire every `interval`, counting only the CPU
+/// time actually spent running this thread (not wall clock), which is
+/// what lets SIGVTALRM drive fair preemption between stacks.
+#[cfg(target_arch = "x86_64")]
+unsafe fn arm_virtual_timer(interval: Duration) {
+    let tick = libc::timeval {
+        tv_sec: interval.as_secs() as libc::time_t,
+        tv_usec: interval.subsec_micros() as libc::suseconds_t,
+    };
+    let timer = libc::itimerval {
+        it_interval: tick,
+        it_value: tick,
+    };
+    if libc::setitimer(libc::ITIMER_VIRTUAL, &timer, std::ptr::null_mut()) != 0 {
+        panic!("setitimer failed: {}", std::io::Error::last_os_error());
+    }
+}
+
+/// SIGVTALRM handler driving preemptive scheduling for whichever worker
+/// owns the thread it fires on. Runs on that thread's alternate signal
+/// stack installed by `install_sigvtalrm_handler`, with `SA_SIGINFO` so
+/// `ctx` is the kernel's `ucontext_t` for the thread at the instant the
+/// timer fired - the interrupted stack's *real* register state, unlike
+/// what calling the cooperative `schedule_next`/`switch_and_run` path
+/// from in here would save (the handler's own alt-stack frame).
+///
+/// Rather than longjmp-ing across stacks ourselves, the handler only
+/// edits `ctx` in place and then returns normally: `sigreturn` is what
+/// actually lands the thread on the chosen stack, and restores
+/// `uc_sigmask` right along with the registers, so the signal mask comes
+/// along for free without any manual save/restore of it here.
+///
+/// Every lock this handler can reach - `ChaseLevDeque::overflow`'s, and
+/// `STACK_REGIONS`'s/the pool's `free_buffers()` via whatever `Stack::drop`
+/// a scheduling site runs while holding `curr_stack` - is guarded by the
+/// `IN_SWITCH` check below, so a tick landing mid-lock just defers to the
+/// next one instead of self-deadlocking.
+#[cfg(target_arch = "x86_64")]
+extern "C" fn sigvtalrm_handler(
+    _sig: libc::c_int,
+    _info: *mut libc::siginfo_t,
+    ctx: *mut libc::c_void,
+) {
+    if IN_SWITCH.with(|f| f.get()) {
+        // A switch is already in flight on this worker; drop this tick and
+        // let the periodic itimer fire again once it clears.
+        return;
+    }
+
+    let worker_ptr = WORKER_PTR.with(|p| p.get());
+    if worker_ptr.is_null() {
+        return;
+    }
+
+    unsafe {
+        let worker = &mut *worker_ptr;
+        if worker.curr_stack.is_none() {
+            // Between stacks in the scheduling loop itself, not inside a
+            // coroutine - nothing meaningful to preempt.
+            return;
+        }
+        if let Some(curr) = worker.curr_stack.as_mut() {
+            if curr.state != State::AVAIABLE {
+                curr.state = State::READY;
+            }
+        }
+
+        IN_SWITCH.with(|f| f.set(true));
+        let next = worker.find_next();
+        IN_SWITCH.with(|f| f.set(false));
+
+        let mut next = match next {
+            Some(next) => next,
+            None => {
+                // Nothing else runnable anywhere; let the interrupted
+                // stack carry straight on.
+                if let Some(curr) = worker.curr_stack.as_mut() {
+                    curr.state = State::RUNNING;
+                }
+                return;
+            }
+        };
+        next.state = State::RUNNING;
+
+        let gregs = &mut (*(ctx as *mut libc::ucontext_t)).uc_mcontext.gregs;
+        let real_rip = gregs[libc::REG_RIP as usize] as u64;
+        let real_rsp = gregs[libc::REG_RSP as usize] as u64;
+
+        let mut paused = worker.curr_stack.replace(next).unwrap();
+
+        // Record the interrupted `rip`/`rsp` as-is in `paused.stack_context`
+        // - no write to the interrupted stack itself. `switch_and_run`'s
+        // `jmp r11` (loaded from `[rsi + 0x80]`, see its naked_asm) and the
+        // resume below both read `rip`/`rsp` straight out of the struct, so
+        // this stack is resumable identically whether it's next picked up
+        // by another preemption tick or by an ordinary cooperative switch - and
+        // unlike synthesizing a return cell at `real_rsp - 8`, this can
+        // never clobber the interrupted frame's red zone.
+        paused.stack_context.rip = real_rip;
+        paused.stack_context.rsp = real_rsp;
+        paused.stack_context.r15 = gregs[libc::REG_R15 as usize] as u64;
+        paused.stack_context.r14 = gregs[libc::REG_R14 as usize] as u64;
+        paused.stack_context.r13 = gregs[libc::REG_R13 as usize] as u64;
+        paused.stack_context.r12 = gregs[libc::REG_R12 as usize] as u64;
+        paused.stack_context.rbx = gregs[libc::REG_RBX as usize] as u64;
+        paused.stack_context.rbp = gregs[libc::REG_RBP as usize] as u64;
+        paused.stack_context.rdi = gregs[libc::REG_RDI as usize] as u64;
+        paused.stack_context.rsi = gregs[libc::REG_RSI as usize] as u64;
+        paused.stack_context.rax = gregs[libc::REG_RAX as usize] as u64;
+        paused.stack_context.rcx = gregs[libc::REG_RCX as usize] as u64;
+        paused.stack_context.rdx = gregs[libc::REG_RDX as usize] as u64;
+        paused.stack_context.r8 = gregs[libc::REG_R8 as usize] as u64;
+        paused.stack_context.r9 = gregs[libc::REG_R9 as usize] as u64;
+        paused.stack_context.r10 = gregs[libc::REG_R10 as usize] as u64;
+        paused.stack_context.r11 = gregs[libc::REG_R11 as usize] as u64;
+        paused.stack_context.rflags = gregs[libc::REG_EFL as usize] as u64;
+
+        if paused.state == State::READY {
+            worker.local_queue.push_bottom(paused);
+        }
+
+        // Point the kernel-restored context at the stack we picked, reading
+        // `rip`/`rsp` straight out of its `StackContext` - `sigreturn`
+        // landing there looks exactly like `switch_and_run`'s explicit
+        // `jmp` would have.
+        let next_context = &worker.curr_stack.as_ref().unwrap().stack_context;
+        gregs[libc::REG_RIP as usize] = next_context.rip as i64;
+        gregs[libc::REG_RSP as usize] = next_context.rsp as i64;
+        gregs[libc::REG_R15 as usize] = next_context.r15 as i64;
+        gregs[libc::REG_R14 as usize] = next_context.r14 as i64;
+        gregs[libc::REG_R13 as usize] = next_context.r13 as i64;
+        gregs[libc::REG_R12 as usize] = next_context.r12 as i64;
+        gregs[libc::REG_RBX as usize] = next_context.rbx as i64;
+        gregs[libc::REG_RBP as usize] = next_context.rbp as i64;
+        gregs[libc::REG_RDI as usize] = next_context.rdi as i64;
+        gregs[libc::REG_RSI as usize] = next_context.rsi as i64;
+        gregs[libc::REG_RAX as usize] = next_context.rax as i64;
+        gregs[libc::REG_RCX as usize] = next_context.rcx as i64;
+        gregs[libc::REG_RDX as usize] = next_context.rdx as i64;
+        gregs[libc::REG_R8 as usize] = next_context.r8 as i64;
+        gregs[libc::REG_R9 as usize] = next_context.r9 as i64;
+        gregs[libc::REG_R10 as usize] = next_context.r10 as i64;
+        gregs[libc::REG_R11 as usize] = next_context.r11 as i64;
+        gregs[libc::REG_EFL as usize] = next_context.rflags as i64;
+    }
 }
 
 #[no_mangle]
 fn finish_and_next_stack() {
+    let worker_ptr = WORKER_PTR.with(|p| p.get());
     unsafe {
-        if (*RUNTIME_PTR).curr_stack_id != PROCESS_MAIN_STACK_ID {
-            (*RUNTIME_PTR).stack_pool[(*RUNTIME_PTR).curr_stack_id].state = State::AVAIABLE;
-            (*RUNTIME_PTR).switch_stack(ScheduleType::RR);
+        let worker = &mut *worker_ptr;
+        let resume_into = match &mut worker.curr_stack {
+            Some(curr) => {
+                curr.state = State::AVAIABLE;
+                curr.resume_into.take()
+            }
+            None => None,
+        };
+
+        match resume_into {
+            // This stack was driven by `resume`, not the ordinary
+            // scheduler: switch straight back there so a `Done` looks
+            // like an ordinary function return to whoever resumed it,
+            // instead of falling into the work-stealing loop. The
+            // now-`AVAIABLE` stack is left in `curr_stack` to be dropped
+            // later the same way any other finished stack is.
+            Some(return_to) => {
+                IN_SWITCH.with(|f| f.set(true));
+                let paused_stack_context: *mut StackContext =
+                    &mut worker.curr_stack.as_mut().unwrap().stack_context;
+                asm!("call switch_and_run", in("rdi") paused_stack_context, in("rsi") return_to, clobber_abi("C"));
+                IN_SWITCH.with(|f| f.set(false));
+            }
+            None => {
+                if !worker.finish_current_and_advance() {
+                    // `find_next` came up empty and this stack (just
+                    // marked `AVAIABLE` above) has genuinely finished.
+                    // This function was entered via the fabricated `ret`
+                    // trampoline `prepare_stack_entry` plants, not a real
+                    // `call`, so falling off its end as a normal return
+                    // would pop whatever garbage sits below it on this
+                    // now-dead stack. Switch back to the worker's own
+                    // native context instead, so it's the
+                    // `while worker.schedule_next() {}` loop in
+                    // `spawn_workers` that actually unwinds and exits.
+                    worker.switch_to_host();
+                }
+            }
         }
     };
 }
 
 pub fn next_stack() {
+    let worker_ptr = WORKER_PTR.with(|p| p.get());
     unsafe {
-        (*RUNTIME_PTR).switch_stack(ScheduleType::RR);
+        (*worker_ptr).schedule_next();
     };
 }
 
 pub fn goto_main() {
+    let worker_ptr = WORKER_PTR.with(|p| p.get());
     unsafe {
-        (*RUNTIME_PTR).terminate_stacks();
+        (*worker_ptr).terminate();
     };
 }
 
 pub fn stack_to(id: usize) {
+    let worker_ptr = WORKER_PTR.with(|p| p.get());
     unsafe {
-        (*RUNTIME_PTR).switch_stack_to(id);
+        (*worker_ptr).switch_stack_to(id);
     };
 }
 
-pub fn trigger_stack_to(id: usize, event: Event) {
+/// Suspends the running stack with `signal`, switching back to whoever
+/// last called `resume` on it (or this worker's `host_context` if it was
+/// never resumed - e.g. a seed stack yielding on its own initiative).
+/// The stack is parked in `suspended_stacks` (state -> `SUSPENDED`) so a
+/// later `resume` can find and re-drive it.
+pub fn stack_yield<T: Any + Send>(signal: SchedSignal<T>) {
+    let worker_ptr = WORKER_PTR.with(|p| p.get());
     unsafe {
-        (*RUNTIME_PTR).trigger_stack_func(id, event);
-    };
+        let worker = &mut *worker_ptr;
+        let mut stack = worker
+            .curr_stack
+            .take()
+            .expect("stack_yield called with no stack running");
+        stack.state = State::SUSPENDED;
+        stack.yielded = match signal {
+            SchedSignal::Yield(value) => {
+                let boxed: Message = Box::new(value);
+                Some(boxed)
+            }
+        };
+        let return_to: *const StackContext = stack
+            .resume_into
+            .take()
+            .map(|p| p as *const StackContext)
+            .unwrap_or(&worker.host_context as *const _);
+        let stack_id = stack.stack_id;
+
+        // Boxed so its address (and thus the `StackContext` pointer the
+        // asm switch below saves into) stays stable even if the table
+        // storing it rehashes while it's parked.
+        let boxed = Box::new(stack);
+        let paused_stack_context: *mut StackContext = &boxed.stack_context as *const _ as *mut _;
+        suspended_stacks().lock().unwrap().insert(stack_id, boxed);
+
+        IN_SWITCH.with(|f| f.set(true));
+        asm!("call switch_and_run", in("rdi") paused_stack_context, in("rsi") return_to, clobber_abi("C"));
+        IN_SWITCH.with(|f| f.set(false));
+    }
+}
+
+/// Resumes a stack previously suspended by `stack_yield`, switching
+/// directly into it, and reads back whatever it yielded once it either
+/// yields again or runs to completion. Returns `None` if `id` was never
+/// suspended - it finished already, or never called `stack_yield`.
+///
+/// Mirrors `switch_stack_to` in spirit, but the target lives in the
+/// global `suspended_stacks` table rather than this worker's own queue,
+/// since a suspended stack isn't owned by any worker until resumed.
+pub fn resume<T: Any + Send>(id: usize) -> Option<T> {
+    let mut target = suspended_stacks().lock().unwrap().remove(&id)?;
+    let worker_ptr = WORKER_PTR.with(|p| p.get());
+    unsafe {
+        let worker = &mut *worker_ptr;
+        target.state = State::RUNNING;
+
+        let paused = worker.curr_stack.take();
+        let paused_stack_context: *mut StackContext = match &paused {
+            Some(stack) => &stack.stack_context as *const _ as *mut _,
+            None => &mut worker.host_context,
+        };
+        target.resume_into = Some(paused_stack_context);
+        worker.curr_stack = Some(*target);
+
+        IN_SWITCH.with(|f| f.set(true));
+        let ready_stack_context: *const StackContext =
+            &worker.curr_stack.as_ref().unwrap().stack_context;
+        asm!("call switch_and_run", in("rdi") paused_stack_context, in("rsi") ready_stack_context, clobber_abi("C"));
+        IN_SWITCH.with(|f| f.set(false));
+
+        // Control resumes here once the target suspends again (via
+        // `stack_yield`) or finishes (via `finish_and_next_stack`) and
+        // switches back through `resume_into`. Direct switches never
+        // cross OS threads, so `worker` is still this same worker.
+        worker.curr_stack = paused;
+
+        suspended_stacks()
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .and_then(|mut s| s.yielded.take())
+            .map(|msg| {
+                *msg.downcast::<T>()
+                    .expect("yielded value type did not match resume::<T>()")
+            })
+    }
 }
 
 pub fn get_current_stack_id() -> usize {
-    unsafe { (*RUNTIME_PTR).curr_stack_id }
+    let worker_ptr = WORKER_PTR.with(|p| p.get());
+    unsafe {
+        (*worker_ptr)
+            .curr_stack
+            .as_ref()
+            .map(|s| s.stack_id)
+            .unwrap_or(0)
+    }
 }
 
 pub fn main() {
     let mut runtime = LeydiStacks::new();
 
-    runtime.new_stack(func1, stack1_trigger);
-    runtime.new_stack(func2, stack2_trigger);
-    runtime.new_stack(func3, stack3_trigger);
-    runtime.new_stack(func4, stack4_trigger);
+    // Seed stacks are numbered in registration order (1-based), so func1
+    // knows func2's mailbox is stack id 2 without any out-of-band wiring.
+    runtime.new_stack(func1);
+    runtime.new_stack(func2);
+    runtime.new_stack(func3);
+    runtime.new_stack(func4);
 
     runtime.run();
 
@@ -340,11 +1763,13 @@ pub fn main() {
 }
 
 fn func1() {
+    let _ = send_to(2, "hello from stack 1");
     println!("func 1");
 }
 
 fn func2() {
-    println!("func 2");
+    let msg: &'static str = recv();
+    println!("stack {} received: {}", get_current_stack_id(), msg);
 }
 
 fn func3() {
@@ -355,34 +1780,260 @@ fn func4() {
     println!("func 4");
 }
 
-pub fn stack1_trigger(from_stack_id: usize, _data_buff_index: usize) {
-    println!(
-        "stack1_trigger called from {} to {}",
-        from_stack_id,
-        get_current_stack_id()
-    );
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // None of `pool_stats()`, `free_buffers()`, `STACK_REGIONS`,
+    // `mailboxes()` or `suspended_stacks()` reset between tests - they're
+    // process-global for the same reason they're global at runtime (see
+    // their doc comments). `cargo test` runs test functions concurrently
+    // by default, so anything here that touches one of them serializes
+    // on this lock first to avoid one test's bookkeeping corrupting
+    // another's.
+    static TEST_SERIAL: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn chase_lev_deque_push_pop_and_steal() {
+        let _guard = TEST_SERIAL.lock().unwrap();
+
+        let deque = ChaseLevDeque::new(4);
+        assert!(deque.pop_bottom().is_none());
+        assert!(deque.steal().is_none());
+
+        let a = Stack::new(9001, State::READY, 1);
+        let b = Stack::new(9002, State::READY, 1);
+        deque.push_bottom(a);
+        deque.push_bottom(b);
+
+        // Owner end is LIFO: the most recently pushed stack comes back
+        // first.
+        let popped = deque.pop_bottom().expect("owner pop should find a stack");
+        assert_eq!(popped.stack_id, 9002);
+
+        // Thief end is FIFO: the oldest surviving stack comes back first.
+        let third = Stack::new(9003, State::READY, 1);
+        deque.push_bottom(third);
+        let stolen = deque.steal().expect("steal should find a stack");
+        assert_eq!(stolen.stack_id, 9001);
+
+        let remaining = deque.pop_bottom().expect("owner pop should find the last stack");
+        assert_eq!(remaining.stack_id, 9003);
+        assert!(deque.pop_bottom().is_none());
+        assert!(deque.steal().is_none());
+    }
 
-pub fn stack2_trigger(from_stack_id: usize, _data_buff_index: usize) {
-    println!(
-        "stack2_trigger called from {} to {}",
-        from_stack_id,
-        get_current_stack_id()
-    );
-}
+    #[test]
+    fn chase_lev_deque_spills_into_overflow_past_capacity() {
+        let _guard = TEST_SERIAL.lock().unwrap();
 
-pub fn stack3_trigger(from_stack_id: usize, _data_buff_index: usize) {
-    println!(
-        "stack3_trigger called from {} to {}",
-        from_stack_id,
-        get_current_stack_id()
-    );
-}
+        let deque = ChaseLevDeque::new(1);
+        deque.push_bottom(Stack::new(9011, State::READY, 1));
+        deque.push_bottom(Stack::new(9012, State::READY, 1)); // spills into `overflow`
+        deque.push_bottom(Stack::new(9013, State::READY, 1)); // spills into `overflow`
 
-pub fn stack4_trigger(from_stack_id: usize, _data_buff_index: usize) {
-    println!(
-        "stack4_trigger called from {} to {}",
-        from_stack_id,
-        get_current_stack_id()
-    );
+        let mut seen = Vec::new();
+        while let Some(stack) = deque.pop_bottom() {
+            seen.push(stack.stack_id);
+        }
+        seen.sort_unstable();
+        assert_eq!(seen, vec![9011, 9012, 9013]);
+    }
+
+    #[test]
+    fn stack_guard_region_registered_on_new_and_deregistered_on_drop() {
+        let _guard = TEST_SERIAL.lock().unwrap();
+
+        let before = STACK_REGIONS.lock().unwrap().len();
+        let stack = Stack::new(9101, State::AVAIABLE, 2);
+        let guard_base = stack.stack_buffer.base_ptr as usize;
+        let guard_len = stack.stack_buffer.guard_bytes;
+        assert_eq!(guard_len, 2 * PAGE_SIZE);
+
+        {
+            let regions = STACK_REGIONS.lock().unwrap();
+            assert_eq!(regions.len(), before + 1);
+            assert!(regions.contains(&(9101, guard_base, guard_len)));
+        }
+
+        drop(stack);
+
+        let regions = STACK_REGIONS.lock().unwrap();
+        assert_eq!(regions.len(), before);
+        assert!(!regions.iter().any(|&(id, _, _)| id == 9101));
+    }
+
+    #[test]
+    fn stack_pool_recycles_buffers_and_enforces_max_ceiling() {
+        let _guard = TEST_SERIAL.lock().unwrap();
+
+        let stats = pool_stats();
+        let saved_max = stats.max_stacks.load(Ordering::Relaxed);
+        let saved_high = stats.high_watermark.load(Ordering::Relaxed);
+        let saved_low = stats.low_watermark.load(Ordering::Relaxed);
+        let saved_free: Vec<MmapStack> = std::mem::take(&mut *free_buffers().lock().unwrap());
+
+        stats.max_stacks.store(2, Ordering::Relaxed);
+        stats.high_watermark.store(1, Ordering::Relaxed);
+        stats.low_watermark.store(0, Ordering::Relaxed);
+
+        let one = Stack::new(9201, State::AVAIABLE, 1);
+        let base_ptr = one.stack_buffer.base_ptr;
+        drop(one);
+
+        // Recycled into `free_buffers()` instead of munmapped, since the
+        // 1-buffer high watermark wasn't exceeded.
+        assert_eq!(free_buffers().lock().unwrap().len(), 1);
+
+        // Requesting the same guard-page count pulls the cached buffer
+        // back out instead of mmapping a fresh one.
+        let two = Stack::new(9202, State::AVAIABLE, 1);
+        assert_eq!(two.stack_buffer.base_ptr, base_ptr);
+        assert_eq!(free_buffers().lock().unwrap().len(), 0);
+
+        let three = Stack::new(9203, State::AVAIABLE, 1);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            Stack::new(9204, State::AVAIABLE, 1)
+        }));
+        assert!(
+            result.is_err(),
+            "Stack::new should panic once live stacks exceed the max_stacks ceiling"
+        );
+
+        drop(two);
+        drop(three);
+
+        stats.max_stacks.store(saved_max, Ordering::Relaxed);
+        stats.high_watermark.store(saved_high, Ordering::Relaxed);
+        stats.low_watermark.store(saved_low, Ordering::Relaxed);
+        *free_buffers().lock().unwrap() = saved_free;
+    }
+
+    static MAILBOX_TEST_RESULT: Mutex<Option<&'static str>> = Mutex::new(None);
+
+    // Registered as seed stack id 1 (round-robin assignment is by
+    // registration order - see `spawn_workers`).
+    fn mailbox_test_sender() {
+        send_to(2, "ping-from-1").expect("mailbox for stack 2 should not be full");
+    }
+
+    // Registered as seed stack id 2. With a single worker, seed stacks are
+    // pushed onto the same `local_queue` in registration order and popped
+    // LIFO, so this one actually runs *before* the sender - it blocks on
+    // an empty mailbox, `find_next` picks up the sender instead, and only
+    // once the sender's `send_to` parks this stack back onto the queue
+    // does it get a second turn and observe the message. That is exactly
+    // the empty-check-then-park path `block_on_mailbox`/`send_to` share a
+    // single `mailboxes()` lock to keep race-free.
+    fn mailbox_test_receiver() {
+        let msg: &'static str = recv();
+        *MAILBOX_TEST_RESULT.lock().unwrap() = Some(msg);
+    }
+
+    #[test]
+    fn mailbox_send_recv_round_trip_via_blocking_wakeup() {
+        let _guard = TEST_SERIAL.lock().unwrap();
+        *MAILBOX_TEST_RESULT.lock().unwrap() = None;
+
+        let mut runtime = LeydiStacks::new().with_worker_count(1);
+        runtime.new_stack(mailbox_test_sender);
+        runtime.new_stack(mailbox_test_receiver);
+        runtime.run();
+
+        assert_eq!(*MAILBOX_TEST_RESULT.lock().unwrap(), Some("ping-from-1"));
+    }
+
+    static GENERATOR_TEST_RESULT: Mutex<(Option<i32>, Option<i32>)> = Mutex::new((None, None));
+
+    // Registered as seed stack id 2, so it runs first (see
+    // `mailbox_test_receiver`) and yields 10 entirely on its own
+    // initiative, before `generator_test_driver` ever calls `resume` -
+    // `resume_into` is still unset at that point, so `stack_yield` routes
+    // the switch back to the worker's host context, same as any other
+    // seed stack's first suspension. Only the *second* `stack_yield`
+    // switches straight back to the driver, because the intervening
+    // `resume` call is what sets `resume_into`.
+    fn generator_test_body() {
+        stack_yield(SchedSignal::Yield(10));
+        stack_yield(SchedSignal::Yield(20));
+    }
+
+    // Registered as seed stack id 1.
+    fn generator_test_driver() {
+        let second_yield: Option<i32> = resume(2);
+        let after_finish: Option<i32> = resume(2);
+        *GENERATOR_TEST_RESULT.lock().unwrap() = (second_yield, after_finish);
+    }
+
+    #[test]
+    fn generator_yield_resume_round_trip() {
+        let _guard = TEST_SERIAL.lock().unwrap();
+        *GENERATOR_TEST_RESULT.lock().unwrap() = (None, None);
+
+        let mut runtime = LeydiStacks::new().with_worker_count(1);
+        runtime.new_stack(generator_test_driver);
+        runtime.new_stack(generator_test_body);
+        runtime.run();
+
+        // The first `resume` lands after the body's second `stack_yield`,
+        // surfacing that yield's value. The second `resume` drives the
+        // body to completion (no further `stack_yield`), so `resume`
+        // returns `None` - the same "already finished" result as
+        // resuming an id nobody ever suspended.
+        assert_eq!(
+            *GENERATOR_TEST_RESULT.lock().unwrap(),
+            (Some(20), None)
+        );
+    }
+
+    static PREEMPT_COUNTER_A: AtomicUsize = AtomicUsize::new(0);
+    static PREEMPT_COUNTER_B: AtomicUsize = AtomicUsize::new(0);
+    static PREEMPT_DEADLINE: Mutex<Option<std::time::Instant>> = Mutex::new(None);
+
+    // Registered as seed stack id 1. Never calls `stack_yield`/`next_stack`
+    // - the only way this and `preempt_test_loop_b` interleave on the
+    // single worker below is `sigvtalrm_handler` forcibly switching one out
+    // mid-loop, which is exactly what `run_preemptive` is for.
+    fn preempt_test_loop_a() {
+        let deadline = PREEMPT_DEADLINE.lock().unwrap().unwrap();
+        while std::time::Instant::now() < deadline {
+            PREEMPT_COUNTER_A.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Registered as seed stack id 2.
+    fn preempt_test_loop_b() {
+        let deadline = PREEMPT_DEADLINE.lock().unwrap().unwrap();
+        while std::time::Instant::now() < deadline {
+            PREEMPT_COUNTER_B.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn run_preemptive_interleaves_busy_loop_stacks_on_one_worker() {
+        let _guard = TEST_SERIAL.lock().unwrap();
+        PREEMPT_COUNTER_A.store(0, Ordering::Relaxed);
+        PREEMPT_COUNTER_B.store(0, Ordering::Relaxed);
+        *PREEMPT_DEADLINE.lock().unwrap() = Some(std::time::Instant::now() + Duration::from_millis(100));
+
+        let mut runtime = LeydiStacks::new()
+            .with_worker_count(1)
+            .with_scheduler_interval(Duration::from_millis(2));
+        runtime.new_stack(preempt_test_loop_a);
+        runtime.new_stack(preempt_test_loop_b);
+        runtime.run_preemptive();
+
+        // Neither loop ever yields control on its own initiative. On a
+        // single worker, the only way stack 2 gets to run at all - let
+        // alone interleaved with stack 1's still-running loop - is the
+        // SIGVTALRM handler preempting stack 1 mid-loop and scheduling
+        // stack 2 in its place.
+        let a = PREEMPT_COUNTER_A.load(Ordering::Relaxed);
+        let b = PREEMPT_COUNTER_B.load(Ordering::Relaxed);
+        assert!(a > 0, "stack 1 should have run at all");
+        assert!(
+            b > 0,
+            "stack 2 should have made progress despite stack 1's busy loop never yielding"
+        );
+    }
 }